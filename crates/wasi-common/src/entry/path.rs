@@ -1,10 +1,22 @@
 use super::{Entry, EntryHandle};
 use crate::handle::{Fdflags, Filestat, Fstflags, HandleRights, Lookupflags, Oflags, Rights, Size};
 use crate::sched::Timestamp;
-use crate::Result;
+use crate::{Error, Result};
+use bitflags::bitflags;
 use std::convert::TryInto;
 use tracing::trace;
 
+bitflags! {
+    /// Flags controlling the behavior of `Entry::path_rename_with_flags` when the
+    /// destination path already exists.
+    pub struct RenameFlags: u32 {
+        /// Fail the rename instead of silently replacing an existing `new_path`.
+        const NOREPLACE = 0b01;
+        /// Atomically swap `old_path` and `new_path`, both of which must already exist.
+        const EXCHANGE = 0b10;
+    }
+}
+
 impl Entry {
     pub fn path_create_directory(&self, path: &str) -> Result<()> {
         let required_rights =
@@ -131,6 +143,18 @@ impl Entry {
     }
 
     pub fn path_rename(&self, old_path: &str, new_entry: &Entry, new_path: &str) -> Result<()> {
+        self.path_rename_with_flags(old_path, new_entry, new_path, RenameFlags::empty())
+    }
+
+    /// Like `path_rename`, but with the `renameat2`-style `flags` controlling what happens
+    /// if `new_path` already exists.
+    pub fn path_rename_with_flags(
+        &self,
+        old_path: &str,
+        new_entry: &Entry,
+        new_path: &str,
+        flags: RenameFlags,
+    ) -> Result<()> {
         let required_rights = HandleRights::from_base(Rights::PATH_RENAME_SOURCE);
         let (old_dirfd, old_path) = crate::path::get(
             &self,
@@ -146,6 +170,23 @@ impl Entry {
             new_path,
             true,
         )?;
-        old_dirfd.rename(&old_path, new_dirfd, &new_path)
+        if flags.contains(RenameFlags::NOREPLACE | RenameFlags::EXCHANGE) {
+            // `renameat2`-style semantics: the two flags are mutually exclusive.
+            return Err(Error::EINVAL);
+        }
+        if flags.contains(RenameFlags::NOREPLACE) {
+            old_dirfd.rename_noreplace(&old_path, new_dirfd, &new_path)
+        } else if flags.contains(RenameFlags::EXCHANGE) {
+            old_dirfd.rename_exchange(&old_path, new_dirfd, &new_path)
+        } else {
+            old_dirfd.rename(&old_path, new_dirfd, &new_path)
+        }
+    }
+
+    pub fn path_symlink(&self, old_path: &str, new_path: &str) -> Result<()> {
+        let required_rights = HandleRights::from_base(Rights::PATH_SYMLINK);
+        let (dirfd, new_path) =
+            crate::path::get(&self, &required_rights, Lookupflags::empty(), new_path, true)?;
+        dirfd.symlink_at(old_path, &new_path)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,180 @@
+//! The `Handle` trait: the common abstraction behind every file descriptor a sandboxed
+//! guest can hold, along with the WASI-level types (rights, flags, `Filestat`) that travel
+//! alongside it.
+
+use crate::sched::Timestamp;
+use crate::{Error, Result};
+use bitflags::bitflags;
+
+/// A WASI file size, in bytes.
+pub type Size = u64;
+
+bitflags! {
+    /// The rights that can be associated with a file descriptor, controlling which
+    /// operations are permitted on it and on file descriptors derived from it.
+    pub struct Rights: u64 {
+        const FD_DATASYNC = 1 << 0;
+        const FD_READ = 1 << 1;
+        const FD_SEEK = 1 << 2;
+        const FD_FDSTAT_SET_FLAGS = 1 << 3;
+        const FD_SYNC = 1 << 4;
+        const FD_TELL = 1 << 5;
+        const FD_WRITE = 1 << 6;
+        const FD_ADVISE = 1 << 7;
+        const FD_ALLOCATE = 1 << 8;
+        const PATH_CREATE_DIRECTORY = 1 << 9;
+        const PATH_CREATE_FILE = 1 << 10;
+        const PATH_LINK_SOURCE = 1 << 11;
+        const PATH_LINK_TARGET = 1 << 12;
+        const PATH_OPEN = 1 << 13;
+        const FD_READDIR = 1 << 14;
+        const PATH_READLINK = 1 << 15;
+        const PATH_RENAME_SOURCE = 1 << 16;
+        const PATH_RENAME_TARGET = 1 << 17;
+        const PATH_FILESTAT_GET = 1 << 18;
+        const PATH_FILESTAT_SET_SIZE = 1 << 19;
+        const PATH_FILESTAT_SET_TIMES = 1 << 20;
+        const FD_FILESTAT_GET = 1 << 21;
+        const FD_FILESTAT_SET_SIZE = 1 << 22;
+        const FD_FILESTAT_SET_TIMES = 1 << 23;
+        const PATH_SYMLINK = 1 << 24;
+        const PATH_REMOVE_DIRECTORY = 1 << 25;
+        const PATH_UNLINK_FILE = 1 << 26;
+        const POLL_FD_READWRITE = 1 << 27;
+        const SOCK_SHUTDOWN = 1 << 28;
+    }
+}
+
+bitflags! {
+    /// Flags provided to `path_open`.
+    pub struct Oflags: u16 {
+        const CREAT = 1 << 0;
+        const DIRECTORY = 1 << 1;
+        const EXCL = 1 << 2;
+        const TRUNC = 1 << 3;
+    }
+}
+
+bitflags! {
+    /// Flags that apply to a file descriptor after it's opened.
+    pub struct Fdflags: u16 {
+        const APPEND = 1 << 0;
+        const DSYNC = 1 << 1;
+        const NONBLOCK = 1 << 2;
+        const RSYNC = 1 << 3;
+        const SYNC = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Flags controlling how a path is resolved.
+    pub struct Lookupflags: u32 {
+        const SYMLINK_FOLLOW = 1 << 0;
+    }
+}
+
+bitflags! {
+    /// Which fields of a `Filestat` should be updated by `path_filestat_set_times`.
+    pub struct Fstflags: u16 {
+        const ATIM = 1 << 0;
+        const ATIM_NOW = 1 << 1;
+        const MTIM = 1 << 2;
+        const MTIM_NOW = 1 << 3;
+    }
+}
+
+/// The base and inheriting rights carried by a file descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandleRights {
+    pub base: Rights,
+    pub inheriting: Rights,
+}
+
+impl HandleRights {
+    /// Creates a set of rights with distinct base and inheriting rights.
+    pub fn new(base: Rights, inheriting: Rights) -> Self {
+        Self { base, inheriting }
+    }
+
+    /// Creates a set of rights where the inheriting rights equal the base rights.
+    pub fn from_base(base: Rights) -> Self {
+        Self::new(base, base)
+    }
+}
+
+/// A `file_stat` result, as returned by `path_filestat_get`/`fd_filestat_get`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Filestat {
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+    pub size: Size,
+    pub atim: Timestamp,
+    pub mtim: Timestamp,
+    pub ctim: Timestamp,
+}
+
+/// A file descriptor, directory descriptor, or any other WASI-visible handle backed by a
+/// host resource. Implementors wrap some OS-specific descriptor; the default method bodies
+/// below report an operation as unsupported, for handles (or hosts) that can't perform it.
+pub trait Handle {
+    fn create_directory(&self, path: &str) -> Result<()>;
+    fn filestat_get_at(&self, path: &str, follow_symlinks: bool) -> Result<Filestat>;
+    fn filestat_set_times_at(
+        &self,
+        path: &str,
+        atim: Timestamp,
+        mtim: Timestamp,
+        fst_flags: Fstflags,
+        follow_symlinks: bool,
+    ) -> Result<()>;
+    fn link(
+        &self,
+        old_path: &str,
+        new_dirfd: &dyn Handle,
+        new_path: &str,
+        follow_symlinks: bool,
+    ) -> Result<()>;
+    fn openat(
+        &self,
+        path: &str,
+        read: bool,
+        write: bool,
+        oflags: Oflags,
+        fdflags: Fdflags,
+    ) -> Result<Box<dyn Handle>>;
+    fn readlink(&self, path: &str, buf: &mut [u8]) -> Result<Size>;
+    fn remove_directory(&self, path: &str) -> Result<()>;
+    fn rename(&self, old_path: &str, new_dirfd: &dyn Handle, new_path: &str) -> Result<()>;
+
+    /// Creates a symbolic link at `new_path` (relative to `self`) that points at
+    /// `old_path`. Hosts without a symlink primitive available -- or a `Handle` impl that
+    /// hasn't wired one up -- get this fallback, which reports the call as unsupported.
+    fn symlink_at(&self, _old_path: &str, _new_path: &str) -> Result<()> {
+        Err(Error::ENOTSUP)
+    }
+
+    /// Like `rename`, but fails instead of silently replacing `new_path` if it already
+    /// exists (e.g. Linux's `renameat2` with `RENAME_NOREPLACE`). Falls back to
+    /// unsupported on hosts without an atomic no-replace rename.
+    fn rename_noreplace(
+        &self,
+        _old_path: &str,
+        _new_dirfd: &dyn Handle,
+        _new_path: &str,
+    ) -> Result<()> {
+        Err(Error::ENOTSUP)
+    }
+
+    /// Atomically swaps `old_path` and `new_path`, both of which must already exist (e.g.
+    /// Linux's `renameat2` with `RENAME_EXCHANGE`). Falls back to unsupported on hosts
+    /// without an atomic exchange rename.
+    fn rename_exchange(
+        &self,
+        _old_path: &str,
+        _new_dirfd: &dyn Handle,
+        _new_path: &str,
+    ) -> Result<()> {
+        Err(Error::ENOTSUP)
+    }
+}
@@ -0,0 +1,486 @@
+//! High-level Cranelift IR builder.
+//!
+//! Provides a straightforward way to create a Cranelift IR function and fill it with
+//! instructions translated from another representation. This module primarily exposes the
+//! `FunctionBuilder` itself, along with its helper `FunctionBuilderContext`.
+
+use crate::ssa::SSABuilder;
+use crate::variable::Variable as _DefaultVariable;
+use cranelift_codegen::entity::{EntityRef, SecondaryMap};
+use cranelift_codegen::ir::{
+    condcodes::IntCC, DataFlowGraph, Ebb, ExtFuncData, Function, FuncRef, GlobalValue,
+    GlobalValueData, Heap, HeapData, HeapStyle, Inst, InstBuilder, InstBuilderBase,
+    InstructionData, ProgramPoint, Signature, SigRef, TrapCode, Type, Value, ValueLabel,
+    ValueLabelAssignments, ValueLabelStart,
+};
+
+// `ssa::SSABuilder` calls back into this module to attach value labels to the `Ebb`
+// parameters it synthesizes, without needing to know anything about `FunctionBuilder`
+// itself. Keeping the bookkeeping here, next to `def_var`/`set_val_label`, means there is
+// only one place that writes into `func.dfg.values_labels`.
+pub(crate) fn register_value_label_start(
+    func: &mut Function,
+    val: Value,
+    label: ValueLabel,
+    from: ProgramPoint,
+) {
+    let start = ValueLabelStart { from, label };
+    func.dfg
+        .values_labels
+        .get_or_insert_with(Default::default)
+        .entry(val)
+        .or_insert_with(|| ValueLabelAssignments::Starts(Vec::new()))
+        .push(start);
+}
+
+/// Side effects caused by SSA construction that a language frontend may need to know
+/// about to keep its own view of the function in sync: `FunctionBuilder` builds the IR
+/// for you, but if it inserts an `Ebb` parameter or discovers a block is unreachable
+/// behind your back, you may want to re-run your own verification, or prune a block from
+/// your own translation tables.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SideEffects {
+    /// `Ebb`s that received one or more new parameters as SSA construction merged a
+    /// variable's definitions across control flow.
+    pub touched_ebbs: Vec<Ebb>,
+
+    /// `Ebb`s discovered to have no predecessors once their predecessor set was
+    /// completed (i.e. once `seal_block` was called) -- dead code that the frontend may
+    /// want to drop from its own bookkeeping.
+    pub unreachable_ebbs: Vec<Ebb>,
+}
+
+impl SideEffects {
+    pub(crate) fn touch_ebb(&mut self, ebb: Ebb) {
+        if !self.touched_ebbs.contains(&ebb) {
+            self.touched_ebbs.push(ebb);
+        }
+    }
+
+    pub(crate) fn mark_unreachable(&mut self, ebb: Ebb) {
+        if !self.unreachable_ebbs.contains(&ebb) {
+            self.unreachable_ebbs.push(ebb);
+        }
+    }
+
+    /// Merges `other` into `self`. Used both internally, to accumulate side effects
+    /// across a whole function build, and by frontends that collect several
+    /// `SideEffects` values before acting on them.
+    pub fn append(&mut self, other: SideEffects) {
+        for ebb in other.touched_ebbs {
+            self.touch_ebb(ebb);
+        }
+        for ebb in other.unreachable_ebbs {
+            self.mark_unreachable(ebb);
+        }
+    }
+
+    /// `true` if and only if nothing was touched.
+    pub fn is_empty(&self) -> bool {
+        self.touched_ebbs.is_empty() && self.unreachable_ebbs.is_empty()
+    }
+}
+
+/// Temporary object used to build a `Function`.
+///
+/// Holds the state a `FunctionBuilder` needs in between building two independent
+/// functions: the SSA construction bookkeeping and a couple of per-block/per-variable
+/// scratch maps.
+///
+/// The same `FunctionBuilderContext` can be reused to build any number of functions, one
+/// after the other: `FunctionBuilder::finalize` leaves it cleared, but with its backing
+/// storage intact, so lowering many functions only pays the allocation cost once instead
+/// of once per function.
+pub struct FunctionBuilderContext<Variable = _DefaultVariable>
+where
+    Variable: EntityRef + Default,
+{
+    ssa: SSABuilder<Variable>,
+    ebbs: SecondaryMap<Ebb, EbbData>,
+    types: SecondaryMap<Variable, Type>,
+}
+
+/// Per-`Ebb` bookkeeping the builder keeps that isn't part of the function itself.
+#[derive(Clone, Default)]
+struct EbbData {
+    /// `true` until the first instruction (other than an `Ebb` parameter) is appended,
+    /// used to make sure parameters are declared before the block has a body.
+    pristine: bool,
+}
+
+impl<Variable> FunctionBuilderContext<Variable>
+where
+    Variable: EntityRef + Default,
+{
+    /// Creates a `FunctionBuilderContext` structure. The structure is automatically cleared
+    /// on `FunctionBuilder::finalize`, so it can be reused for several functions.
+    pub fn new() -> Self {
+        Self {
+            ssa: SSABuilder::new(),
+            ebbs: SecondaryMap::new(),
+            types: SecondaryMap::new(),
+        }
+    }
+
+    /// Used to reset a `FunctionBuilderContext` before starting to compile a new function.
+    fn clear(&mut self) {
+        self.ssa.clear();
+        self.ebbs.clear();
+        self.types.clear();
+    }
+
+    /// `true` if and only if this context was just created, or was cleared by the last
+    /// `FunctionBuilder::finalize` to use it.
+    fn is_empty(&self) -> bool {
+        self.ssa.is_empty() && self.ebbs.is_empty() && self.types.is_empty()
+    }
+}
+
+/// Provides the additional context necessary to translate a function.
+///
+/// The `FunctionBuilder` is the object through which most of the translation happens. It
+/// wraps the `Function` under construction as well as a `FunctionBuilderContext` holding
+/// the scratch state, and offers an API to declare source-language variables, emit
+/// instructions, and lay out the control-flow graph incrementally.
+pub struct FunctionBuilder<'a, Variable = _DefaultVariable>
+where
+    Variable: EntityRef + Default,
+{
+    /// The function currently being built.
+    pub func: &'a mut Function,
+
+    /// Source-level state kept across the construction of this single function.
+    func_ctx: &'a mut FunctionBuilderContext<Variable>,
+
+    /// The `Ebb` instructions are currently being appended to, if any.
+    position: Option<Ebb>,
+
+    /// Side effects accumulated across the whole function build so far, for callers that
+    /// would rather inspect them once at `finalize` than after every individual call that
+    /// might produce some.
+    side_effects: SideEffects,
+}
+
+impl<'a, Variable> FunctionBuilder<'a, Variable>
+where
+    Variable: EntityRef + Default,
+{
+    /// Creates a new `FunctionBuilder` that will operate on `func` using the scratch state
+    /// held in `func_ctx`. `func_ctx` must either be fresh from `FunctionBuilderContext::new`
+    /// or have been handed back by a previous call to `finalize`: either way it must be
+    /// empty, since its contents are specific to a single function.
+    pub fn new(func: &'a mut Function, func_ctx: &'a mut FunctionBuilderContext<Variable>) -> Self {
+        debug_assert!(
+            func_ctx.is_empty(),
+            "a FunctionBuilderContext must be finalized before being reused for a new function"
+        );
+        Self {
+            func,
+            func_ctx,
+            position: None,
+            side_effects: SideEffects::default(),
+        }
+    }
+
+    /// Creates a new `Ebb` and returns its reference.
+    pub fn create_ebb(&mut self) -> Ebb {
+        let ebb = self.func.dfg.make_ebb();
+        self.func_ctx.ebbs[ebb] = EbbData { pristine: true };
+        ebb
+    }
+
+    /// After the creation of a `Ebb`, you can add parameters to it with this function, that
+    /// correspond to the number and types of the arguments to the function.
+    ///
+    /// This also marks `ebb` as the function's entry block: since it's the one that binds
+    /// the function's incoming arguments, it's necessarily where control enters the
+    /// function, so it's expected to have no predecessors and is exempted from the
+    /// unreachable-block checks that `seal_block`/`use_var` perform on every other `Ebb`.
+    pub fn append_ebb_params_for_function_params(&mut self, ebb: Ebb) {
+        debug_assert!(
+            self.func_ctx.ebbs[ebb].pristine,
+            "ebb parameters must be declared before adding any instructions"
+        );
+        self.func_ctx.ssa.declare_entry_block(ebb);
+        for argtyp in self.func.signature.params.clone() {
+            self.func.dfg.append_ebb_param(ebb, argtyp.value_type);
+        }
+    }
+
+    /// After the creation of a `Ebb`, you can set its final list of parameter types to
+    /// match the function's return types, for Ebbs that act as landing pads.
+    pub fn append_ebb_params_for_function_returns(&mut self, ebb: Ebb) {
+        debug_assert!(
+            self.func_ctx.ebbs[ebb].pristine,
+            "ebb parameters must be declared before adding any instructions"
+        );
+        for argtyp in self.func.signature.returns.clone() {
+            self.func.dfg.append_ebb_param(ebb, argtyp.value_type);
+        }
+    }
+
+    /// Declare that translation of the source program is switching to the given `Ebb`. Future
+    /// calls to `ins()` will insert instructions into this block.
+    ///
+    /// Switching to a block is necessary to insert instructions into it, and it is
+    /// sufficient to do so only once.
+    pub fn switch_to_block(&mut self, ebb: Ebb) {
+        self.position = Some(ebb);
+        if !self.func.layout.is_ebb_inserted(ebb) {
+            self.func.layout.append_ebb(ebb);
+        }
+    }
+
+    /// Declares that all the predecessors of this block are known.
+    ///
+    /// Function to call with `ebb` once all the predecessors of `ebb` have been declared by
+    /// the translator, i.e. every branch or jump instruction targeting `ebb` has been
+    /// emitted. This resolves every variable use that had to be deferred because `ebb`'s
+    /// predecessor set wasn't final yet. Returns the `SideEffects` this resolution caused,
+    /// which are also folded into the accumulator returned by `finalize`.
+    pub fn seal_block(&mut self, ebb: Ebb) -> SideEffects {
+        let effects = self.func_ctx.ssa.seal_block(self.func, ebb);
+        self.side_effects.append(effects.clone());
+        effects
+    }
+
+    /// Effectively calls `seal_block` on all unsealed blocks in the function, returning
+    /// the combined `SideEffects` of doing so.
+    ///
+    /// It's more efficient to seal `Ebb`s as soon as possible, during translation, rather
+    /// than calling `seal_all_blocks` at the end of translation, as it may allow the SSA
+    /// construction algorithm to avoid some redundant work.
+    pub fn seal_all_blocks(&mut self) -> SideEffects {
+        let ebbs: Vec<Ebb> = self.func.layout.ebbs().collect();
+        let mut effects = SideEffects::default();
+        for ebb in ebbs {
+            if !self.func_ctx.ssa.is_sealed(ebb) {
+                effects.append(self.seal_block(ebb));
+            }
+        }
+        effects
+    }
+
+    /// Declares the type of a variable, so that it can be used later (by calling
+    /// `FunctionBuilder::use_var`). This function has to be called at least once before
+    /// calling `use_var` for a given variable.
+    pub fn declare_var(&mut self, var: Variable, ty: Type) {
+        self.func_ctx.types[var] = ty;
+    }
+
+    /// Associates `var` with the debugger-visible `label`: once this is called, every
+    /// `Value` that stands for `var` -- whether defined directly through `def_var` or
+    /// synthesized behind the scenes by SSA construction while merging control flow -- gets
+    /// a `ValueLabelStart` recorded for it, so a debug-info backend can later reconstruct
+    /// `var`'s location at any point in the function.
+    pub fn declare_var_label(&mut self, var: Variable, label: ValueLabel) {
+        self.func_ctx.ssa.declare_var_label(var, label);
+    }
+
+    /// Explicitly associate `label` with an intermediate `value`, for values that don't
+    /// flow through `def_var`/`use_var` at all (for instance a value produced purely to
+    /// feed another instruction) but that should still be visible to a debugger.
+    pub fn set_val_label(&mut self, value: Value, label: ValueLabel) {
+        let from = self.min_inst_program_point();
+        register_value_label_start(self.func, value, label, from);
+    }
+
+    fn min_inst_program_point(&self) -> ProgramPoint {
+        let ebb = self.position.expect("must be in a block to record a value label");
+        match self.func.layout.last_inst(ebb) {
+            Some(inst) => inst.into(),
+            None => ebb.into(),
+        }
+    }
+
+    /// Declares that `val` is the value used to represent `var` from now on, until the end
+    /// of the current block or a future call to `def_var`. If `var` was declared through
+    /// `declare_var_label`, a `ValueLabelStart` is recorded for `val` at this point.
+    pub fn def_var(&mut self, var: Variable, val: Value) {
+        let ebb = self.position.expect("must switch to a block before defining a variable");
+        self.func_ctx.ssa.def_var(var, val, ebb);
+        if let Some(label) = self.func_ctx.ssa.var_label(var) {
+            let from = self.min_inst_program_point();
+            register_value_label_start(self.func, val, label, from);
+        }
+    }
+
+    /// Returns the `Value` holding the current value of the variable `var`. This method
+    /// performs SSA construction on the fly: it may walk back through predecessors or
+    /// synthesize new `Ebb` parameters if `var` hasn't been defined along every path yet.
+    /// Any `SideEffects` this causes are accumulated and returned by `finalize`; use
+    /// `use_var_and_effects` if you need to act on them right away.
+    pub fn use_var(&mut self, var: Variable) -> Value {
+        let (val, effects) = self.use_var_and_effects(var);
+        self.side_effects.append(effects);
+        val
+    }
+
+    /// Like `use_var`, but returns the `SideEffects` the query caused instead of folding
+    /// them into the accumulator returned by `finalize`.
+    pub fn use_var_and_effects(&mut self, var: Variable) -> (Value, SideEffects) {
+        let ebb = self.position.expect("must switch to a block before using a variable");
+        let ty = self.func_ctx.types[var];
+        debug_assert_ne!(
+            ty,
+            Type::default(),
+            "variable must be declared (with declare_var) before it is used"
+        );
+        self.func_ctx.ssa.use_var(self.func, var, ty, ebb)
+    }
+
+    /// Returns `Ebb`'s arguments as relied upon by `use_var()`.
+    pub fn ebb_params(&self, ebb: Ebb) -> &[Value] {
+        self.func.dfg.ebb_params(ebb)
+    }
+
+    /// Creates a parameter for a specific `Ebb` by appending it to the list of already
+    /// existing parameters.
+    pub fn append_ebb_param(&mut self, ebb: Ebb, ty: Type) -> Value {
+        self.func.dfg.append_ebb_param(ebb, ty)
+    }
+
+    /// Declares a heap accessible to the function, returning a reference that can later be
+    /// passed to `heap_addr`.
+    pub fn create_heap(&mut self, data: HeapData) -> Heap {
+        self.func.create_heap(data)
+    }
+
+    /// Declares a global value accessible to the function, returning its reference.
+    pub fn create_global_value(&mut self, data: GlobalValueData) -> GlobalValue {
+        self.func.create_global_value(data)
+    }
+
+    /// Imports a function signature for use in indirect calls, returning its reference.
+    pub fn import_signature(&mut self, signature: Signature) -> SigRef {
+        self.func.import_signature(signature)
+    }
+
+    /// Declares an external function accessible to (and callable from) the function,
+    /// returning its reference.
+    pub fn import_function(&mut self, data: ExtFuncData) -> FuncRef {
+        self.func.import_function(data)
+    }
+
+    /// Returns the native address of `index` bytes (plus `offset`) into `heap`, after
+    /// emitting the bounds check appropriate to the heap's style (`Dynamic`, checked
+    /// against a runtime bound held in a global value, or `Static`, checked against a
+    /// compile-time constant). Traps with `TrapCode::HeapOutOfBounds` if the access would
+    /// run past the end of the heap.
+    ///
+    /// This spares a frontend translating WebAssembly-style linear memory accesses from
+    /// having to duplicate the bounds-check lowering itself.
+    pub fn heap_addr(&mut self, heap: Heap, index: Value, offset: u32, access_size: u32) -> Value {
+        let index_ty = self.func.heaps[heap].index_type;
+        let addr_ty = self.func.heaps[heap].pointer_type();
+        let offset_and_size = i64::from(offset) + i64::from(access_size);
+
+        let oob = match self.func.heaps[heap].style {
+            HeapStyle::Dynamic { bound_gv } => {
+                let bound = self.ins().global_value(index_ty, bound_gv);
+                let adjusted_bound = self.ins().iadd_imm(bound, -offset_and_size);
+                self.ins()
+                    .icmp(IntCC::UnsignedGreaterThan, index, adjusted_bound)
+            }
+            HeapStyle::Static { bound } => {
+                let adjusted_bound = bound.saturating_sub(offset_and_size as u64);
+                // `adjusted_bound` may not fit in `index_ty` (e.g. a ~4 GiB static bound
+                // with a 32-bit index). Clamp it to the type's maximum representable
+                // value first: an index can never exceed that anyway, so the comparison
+                // stays correct (it just never traps for a bound that's already "as large
+                // as possible" for this index width), instead of silently truncating the
+                // immediate to a much smaller, wrong value.
+                let index_max = if index_ty.bits() >= 64 {
+                    u64::max_value()
+                } else {
+                    (1u64 << index_ty.bits()) - 1
+                };
+                let adjusted_bound = std::cmp::min(adjusted_bound, index_max);
+                self.ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThan, index, adjusted_bound as i64)
+            }
+        };
+        self.ins().trapnz(oob, TrapCode::HeapOutOfBounds);
+
+        let base = self.ins().global_value(addr_ty, self.func.heaps[heap].base);
+        let index = if index_ty == addr_ty {
+            index
+        } else {
+            self.ins().uextend(addr_ty, index)
+        };
+        let addr = self.ins().iadd(base, index);
+        if offset == 0 {
+            addr
+        } else {
+            self.ins().iadd_imm(addr, i64::from(offset))
+        }
+    }
+
+    /// Returns an object with the `InstBuilder` trait that allows to insert an instruction
+    /// at the current position of the builder.
+    pub fn ins<'short>(&'short mut self) -> FuncInstBuilder<'short, 'a, Variable> {
+        let ebb = self
+            .position
+            .expect("must switch to a block before inserting instructions");
+        FuncInstBuilder { builder: self, ebb }
+    }
+
+    /// Completes the translation of the current function, consuming the `FunctionBuilder`.
+    /// The resulting `Function` can be found through the `func` field of whoever owned this
+    /// builder. The `FunctionBuilderContext` is cleared (though its backing storage is kept)
+    /// so it is immediately ready to be handed to a new `FunctionBuilder` for the next
+    /// function.
+    ///
+    /// Returns every `SideEffects` accumulated over the course of building this function,
+    /// for callers that would rather process them once here than after each individual
+    /// `use_var`/`seal_block` call.
+    pub fn finalize(self) -> SideEffects {
+        self.func_ctx.clear();
+        debug_assert!(
+            self.func_ctx.is_empty(),
+            "finalize should always leave the FunctionBuilderContext ready for reuse"
+        );
+        self.side_effects
+    }
+}
+
+/// An object implementing `InstBuilder` backed by a `FunctionBuilder`.
+pub struct FuncInstBuilder<'short, 'long: 'short, Variable>
+where
+    Variable: EntityRef + Default,
+{
+    builder: &'short mut FunctionBuilder<'long, Variable>,
+    ebb: Ebb,
+}
+
+impl<'short, 'long, Variable> InstBuilderBase<'short> for FuncInstBuilder<'short, 'long, Variable>
+where
+    Variable: EntityRef + Default,
+{
+    fn data_flow_graph(&self) -> &DataFlowGraph {
+        &self.builder.func.dfg
+    }
+
+    fn data_flow_graph_mut(&mut self) -> &mut DataFlowGraph {
+        &mut self.builder.func.dfg
+    }
+
+    fn build(
+        self,
+        data: InstructionData,
+        ctrl_typevar: Type,
+    ) -> (Inst, &'short mut DataFlowGraph) {
+        let opcode = data.opcode();
+        let is_branch = opcode.is_branch();
+        let inst = self.builder.func.dfg.make_inst(data);
+        self.builder.func.dfg.make_inst_results(inst, ctrl_typevar);
+        self.builder.func.layout.append_inst(inst, self.ebb);
+        self.builder.func_ctx.ebbs[self.ebb].pristine = false;
+        if is_branch {
+            if let Some(dest) = self.builder.func.dfg.analyze_branch(inst).single_dest() {
+                self.builder.func_ctx.ssa.declare_ebb_predecessor(dest, inst);
+            }
+        }
+        (inst, &mut self.builder.func.dfg)
+    }
+}
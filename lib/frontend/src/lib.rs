@@ -173,7 +173,7 @@
 
 extern crate cranelift_codegen;
 
-pub use frontend::{FunctionBuilder, FunctionBuilderContext};
+pub use frontend::{FunctionBuilder, FunctionBuilderContext, SideEffects};
 pub use variable::Variable;
 
 mod frontend;
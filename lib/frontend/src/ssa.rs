@@ -0,0 +1,231 @@
+//! A SSA-building API that handles incomplete CFGs.
+//!
+//! The algorithm is based upon Braun M., Buchwald S., Hack S., Leißa R., Mallon C.,
+//! Zwinkau A. (2013) "Simple and Efficient Construction of Static Single Assignment Form".
+//! Variable uses that cannot yet be resolved (because the current `Ebb` has not seen all
+//! of its predecessors) are recorded and patched up lazily once `seal_block` tells us the
+//! predecessor set is final.
+
+use crate::frontend::SideEffects;
+use cranelift_codegen::entity::{EntityRef, SecondaryMap};
+use cranelift_codegen::ir::{Ebb, Function, Inst, Type, Value, ValueLabel};
+use cranelift_codegen::packed_option::PackedOption;
+
+/// Structure containing the data relevant the construction of SSA for a given function.
+///
+/// The parameter struct `Variable` corresponds to the way variables are represented in the
+/// non-SSA language you're translating from.
+pub(crate) struct SSABuilder<Variable>
+where
+    Variable: EntityRef + Default,
+{
+    /// Records, for every `(Ebb, Variable)` pair seen so far, the `Value` that currently
+    /// stands for that variable along this path of construction.
+    variables: SecondaryMap<Ebb, SecondaryMap<Variable, PackedOption<Value>>>,
+
+    /// Whether `seal_block` has been called on a given `Ebb`: once sealed, its predecessor
+    /// set is known to be final, so pending variable uses can be resolved immediately.
+    sealed: SecondaryMap<Ebb, bool>,
+
+    /// The predecessor instructions (the branches/jumps that target a given `Ebb`)
+    /// recorded so far; filled in by the frontend as it lays out control flow.
+    preds: SecondaryMap<Ebb, Vec<Inst>>,
+
+    /// `Ebb` parameters synthesized speculatively while a block was still unsealed, paired
+    /// with the variable they stand in for. Resolved against the real predecessor set once
+    /// the block is finally sealed.
+    undef_variables: SecondaryMap<Ebb, Vec<(Variable, Value)>>,
+
+    /// The value label, if any, that was associated with a given variable through
+    /// `declare_var_label`. Whenever SSA construction synthesizes a fresh `Value` to stand
+    /// for `var` (an `Ebb` parameter created behind the frontend's back), that value
+    /// inherits this label so debug-info generation doesn't need to know about the merge.
+    var_labels: SecondaryMap<Variable, PackedOption<ValueLabel>>,
+
+    /// The function's entry block, if declared through `declare_entry_block`. It
+    /// legitimately has no predecessors, so it's exempted from the "sealed with no
+    /// predecessors" unreachable-block check that every other `Ebb` gets.
+    entry_block: PackedOption<Ebb>,
+}
+
+impl<Variable> SSABuilder<Variable>
+where
+    Variable: EntityRef + Default,
+{
+    /// Allocate a new blank SSA builder struct.
+    pub(crate) fn new() -> Self {
+        Self {
+            variables: SecondaryMap::new(),
+            sealed: SecondaryMap::new(),
+            preds: SecondaryMap::new(),
+            undef_variables: SecondaryMap::new(),
+            var_labels: SecondaryMap::new(),
+            entry_block: PackedOption::default(),
+        }
+    }
+
+    /// Clears a `SSABuilder` for reuse on a new function, retaining its allocations.
+    pub(crate) fn clear(&mut self) {
+        self.variables.clear();
+        self.sealed.clear();
+        self.preds.clear();
+        self.undef_variables.clear();
+        self.var_labels.clear();
+        self.entry_block = PackedOption::default();
+    }
+
+    /// Returns `true` if and only if no blocks have been declared in this builder.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+            && self.sealed.is_empty()
+            && self.preds.is_empty()
+            && self.undef_variables.is_empty()
+            && self.entry_block.is_none()
+    }
+
+    /// Marks `ebb` as the function's entry block: it is exempt from being reported as
+    /// unreachable once sealed, since having no predecessors is exactly what's expected of
+    /// the entry point.
+    pub(crate) fn declare_entry_block(&mut self, ebb: Ebb) {
+        self.entry_block = PackedOption::from(ebb);
+    }
+
+    /// `true` if and only if `seal_block` has already been called on `ebb`.
+    pub(crate) fn is_sealed(&self, ebb: Ebb) -> bool {
+        self.sealed[ebb]
+    }
+
+    /// Associates `label` with `var`: any `Value` later synthesized by SSA construction to
+    /// stand for `var` will carry this label.
+    pub(crate) fn declare_var_label(&mut self, var: Variable, label: ValueLabel) {
+        self.var_labels[var] = PackedOption::from(label);
+    }
+
+    /// Returns the value label, if any, previously associated with `var` through
+    /// `declare_var_label`.
+    pub(crate) fn var_label(&self, var: Variable) -> Option<ValueLabel> {
+        self.var_labels[var].expand()
+    }
+
+    /// Declares that `val` is the value of `var` for the rest of the current `ebb`.
+    pub(crate) fn def_var(&mut self, var: Variable, val: Value, ebb: Ebb) {
+        self.variables[ebb][var] = PackedOption::from(val);
+    }
+
+    /// Returns the value corresponding to `var` as seen at the end of `ebb`, recursing
+    /// into predecessors and synthesizing `Ebb` parameters as necessary. Returns, along
+    /// with the value, a `SideEffects` describing every block the query had to touch, so
+    /// callers that track value labels or keep their own CFG bookkeeping can act on them.
+    pub(crate) fn use_var(
+        &mut self,
+        func: &mut Function,
+        var: Variable,
+        ty: Type,
+        ebb: Ebb,
+    ) -> (Value, SideEffects) {
+        if let Some(val) = self.variables[ebb][var].expand() {
+            return (val, SideEffects::default());
+        }
+        self.use_var_nonlocal(func, var, ty, ebb)
+    }
+
+    /// Slow path of `use_var`, invoked only the first time a variable is read in a given
+    /// `Ebb`: either defers to sealing (if `ebb` is not yet sealed), short-circuits through
+    /// a single predecessor, or creates a new `Ebb` parameter to merge the variable's value
+    /// across multiple predecessors.
+    fn use_var_nonlocal(
+        &mut self,
+        func: &mut Function,
+        var: Variable,
+        ty: Type,
+        ebb: Ebb,
+    ) -> (Value, SideEffects) {
+        if !self.sealed[ebb] {
+            // The predecessor set isn't final yet: create a parameter now and patch it up
+            // when `seal_block` is eventually called.
+            let val = func.dfg.append_ebb_param(ebb, ty);
+            self.undef_variables[ebb].push((var, val));
+            self.def_var(var, val, ebb);
+            self.assign_label(func, var, val, ebb);
+            let mut effects = SideEffects::default();
+            effects.touch_ebb(ebb);
+            return (val, effects);
+        }
+
+        let preds = self.preds[ebb].clone();
+        if preds.is_empty() {
+            // `ebb` has no predecessors at all once sealed: it is unreachable, unless it's
+            // the function's entry block, which is expected to have none. We still need
+            // *some* value to hand back, so synthesize a parameter that will simply never
+            // be read at run time, and flag the block as dead for the frontend.
+            let val = func.dfg.append_ebb_param(ebb, ty);
+            self.def_var(var, val, ebb);
+            self.assign_label(func, var, val, ebb);
+            let mut effects = SideEffects::default();
+            effects.touch_ebb(ebb);
+            if self.entry_block.expand() != Some(ebb) {
+                effects.mark_unreachable(ebb);
+            }
+            return (val, effects);
+        }
+        if let [single_pred] = preds[..] {
+            // Only one predecessor: the variable's value flows straight through, no
+            // `Ebb` parameter needed.
+            let pred_ebb = func.layout.inst_ebb(single_pred).expect("inserted branch");
+            let (val, effects) = self.use_var(func, var, ty, pred_ebb);
+            self.def_var(var, val, ebb);
+            return (val, effects);
+        }
+
+        // Multiple predecessors: synthesize a parameter to merge them, defining it early
+        // so that a use reachable through a loop back-edge terminates recursion.
+        let val = func.dfg.append_ebb_param(ebb, ty);
+        self.def_var(var, val, ebb);
+        self.assign_label(func, var, val, ebb);
+        let mut effects = SideEffects::default();
+        effects.touch_ebb(ebb);
+        for pred_inst in preds {
+            let pred_ebb = func.layout.inst_ebb(pred_inst).expect("inserted branch");
+            let (pred_val, more) = self.use_var(func, var, ty, pred_ebb);
+            func.dfg.append_inst_arg(pred_inst, pred_val);
+            effects.append(more);
+        }
+        (val, effects)
+    }
+
+    /// Registers `pred` as a predecessor of `ebb`: called by the frontend whenever it
+    /// inserts a branch or jump instruction that targets `ebb`.
+    pub(crate) fn declare_ebb_predecessor(&mut self, ebb: Ebb, pred: Inst) {
+        self.preds[ebb].push(pred);
+    }
+
+    /// Completes the predecessor set of `ebb` and resolves every variable use that had to
+    /// be deferred while it was unsealed. Returns a `SideEffects` describing what the
+    /// resolution touched.
+    pub(crate) fn seal_block(&mut self, func: &mut Function, ebb: Ebb) -> SideEffects {
+        debug_assert!(!self.sealed[ebb], "seal_block called twice on the same block");
+        let pending = std::mem::replace(&mut self.undef_variables[ebb], Vec::new());
+        self.sealed[ebb] = true;
+
+        let mut effects = SideEffects::default();
+        if self.preds[ebb].is_empty() && self.entry_block.expand() != Some(ebb) {
+            effects.mark_unreachable(ebb);
+        }
+        for (var, param_val) in pending {
+            let ty = func.dfg.value_type(param_val);
+            for pred_inst in self.preds[ebb].clone() {
+                let pred_ebb = func.layout.inst_ebb(pred_inst).expect("inserted branch");
+                let (pred_val, more) = self.use_var(func, var, ty, pred_ebb);
+                func.dfg.append_inst_arg(pred_inst, pred_val);
+                effects.append(more);
+            }
+        }
+        effects
+    }
+
+    fn assign_label(&self, func: &mut Function, var: Variable, val: Value, ebb: Ebb) {
+        if let Some(label) = self.var_labels[var].expand() {
+            super::frontend::register_value_label_start(func, val, label, ebb.into());
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Frontend variable handling.
+
+use cranelift_codegen::entity::EntityRef;
+use std::u32;
+
+/// An opaque reference to a variable.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub struct Variable(u32);
+
+impl EntityRef for Variable {
+    fn new(index: usize) -> Self {
+        debug_assert!(index < (u32::MAX as usize));
+        Variable(index as u32)
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Default for Variable {
+    /// Create a `Variable` with a dummy value that must be overridden before it is used.
+    fn default() -> Self {
+        Variable(u32::MAX)
+    }
+}